@@ -1,9 +1,15 @@
-use std::{fmt::Display, iter::Peekable, str::Chars};
+use std::{collections::HashMap, fmt::Display, iter::Peekable, str::Chars};
+
+// Char range (start, end) of a token within the source string, counted in
+// chars rather than bytes.
+type Span = (usize, usize);
 
 // Token Definition
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 enum Token {
-    Number(i32),
+    Number(f64),
+    Identifier(String),
+    Comma,
     Plus,
     Minus,
     Mutiply,
@@ -11,12 +17,24 @@ enum Token {
     Power,
     LeftParen,
     RightParen,
+    Pipe,
+    Ampersand,
+    Xor,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
 }
 
 // Define error type
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ExprError {
-    Parse(String),
+    Parse(String, Option<Span>),
+    UnexpectedCharacter { character: char, position: usize },
+    DivisionByZero,
+    Overflow,
 }
 
 impl std::error::Error for ExprError {}
@@ -24,7 +42,23 @@ impl std::error::Error for ExprError {}
 impl Display for ExprError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Parse(s) => write!(f, "{}", s),
+            Self::Parse(s, _) => write!(f, "{}", s),
+            Self::UnexpectedCharacter { character, position } => {
+                write!(f, "unexpected character '{}' at position {}", character, position)
+            }
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::Overflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl ExprError {
+    // The source span this error refers to, if it carries one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Parse(_, span) => *span,
+            Self::UnexpectedCharacter { position, .. } => Some((*position, *position + 1)),
+            Self::DivisionByZero | Self::Overflow => None,
         }
     }
 }
@@ -40,6 +74,8 @@ impl Display for Token {
             "{}",
             match self {
                 Token::Number(n) => n.to_string(),
+                Token::Identifier(s) => s.clone(),
+                Token::Comma => ",".to_string(),
                 Token::Plus => "+".to_string(),
                 Token::Minus => "-".to_string(),
                 Token::Mutiply => "*".to_string(),
@@ -47,30 +83,48 @@ impl Display for Token {
                 Token::Power => "^".to_string(),
                 Token::LeftParen => "(".to_string(),
                 Token::RightParen => ")".to_string(),
+                Token::Pipe => "|".to_string(),
+                Token::Ampersand => "&".to_string(),
+                Token::Xor => "~".to_string(),
+                Token::Lt => "<".to_string(),
+                Token::Le => "<=".to_string(),
+                Token::Gt => ">".to_string(),
+                Token::Ge => ">=".to_string(),
+                Token::Eq => "==".to_string(),
+                Token::Ne => "!=".to_string(),
             }
         )
     }
 }
 
 impl Token {
-    
+
     fn is_operator(&self) -> bool {
         match self {
-            Token::Plus | Token::Minus | Token::Mutiply | Token::Divide | Token::Power | Token::LeftParen | Token::RightParen => true,
-            _ => false, 
+            Token::Plus | Token::Minus | Token::Mutiply | Token::Divide | Token::Power
+            | Token::LeftParen | Token::RightParen | Token::Pipe | Token::Ampersand | Token::Xor
+            | Token::Lt | Token::Le | Token::Gt | Token::Ge | Token::Eq | Token::Ne => true,
+            _ => false,
         }
     }
 
     // get the precedence level of token
     fn precedence(&self) -> i32 {
         match self {
-            Token::Plus | Token::Minus => 1,
-            Token::Divide | Token::Mutiply => 2,
-            Token::Power => 3,
+            // Comparisons sit below everything else, then bitwise in their
+            // own tiers (mirrors C: `|` loosest, then `~` (xor), then `&`),
+            // then arithmetic.
+            Token::Lt | Token::Le | Token::Gt | Token::Ge | Token::Eq | Token::Ne => 1,
+            Token::Pipe => 2,
+            Token::Xor => 3,
+            Token::Ampersand => 4,
+            Token::Plus | Token::Minus => 5,
+            Token::Divide | Token::Mutiply => 6,
+            Token::Power => 7,
             _ => 0,
         }
     }
-    
+
     //get associative of operator
     fn assoc(&self) -> i32 {
         match self {
@@ -79,96 +133,386 @@ impl Token {
         }
     }
 
-    fn compute(&self, l: i32 , r: i32) -> Option<i32> {
+    fn compute(&self, l: f64, r: f64) -> Result<f64, ExprError> {
         match self {
-            Token::Plus => Some(l + r),
-            Token::Minus => Some(l - r),
-            Token::Mutiply => Some(l * r),
-            Token::Divide => Some(l / r),
-            Token::Power => Some(l.pow(r as u32)),
-            _ => None
+            Token::Plus => checked(l + r),
+            Token::Minus => checked(l - r),
+            Token::Mutiply => checked(l * r),
+            Token::Divide => {
+                if r == 0.0 {
+                    return Err(ExprError::DivisionByZero);
+                }
+                checked(l / r)
+            }
+            Token::Power => checked(l.powf(r)),
+            Token::Ampersand => Ok(((l as i64) & (r as i64)) as f64),
+            Token::Pipe => Ok(((l as i64) | (r as i64)) as f64),
+            Token::Xor => Ok(((l as i64) ^ (r as i64)) as f64),
+            Token::Lt => Ok(bool_to_f64(l < r)),
+            Token::Le => Ok(bool_to_f64(l <= r)),
+            Token::Gt => Ok(bool_to_f64(l > r)),
+            Token::Ge => Ok(bool_to_f64(l >= r)),
+            Token::Eq => Ok(bool_to_f64(l == r)),
+            Token::Ne => Ok(bool_to_f64(l != r)),
+            _ => Err(ExprError::Parse("Unknown operator".into(), None)),
         }
     }
 }
 
+// Relational operators fold down to 0/1, same as `expr`-style utilities.
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// Nonzero is true, mirroring shell/`expr` truthiness.
+fn is_truthy(value: f64) -> bool {
+    value != 0.0
+}
+
+// Reject non-finite results (overflow to infinity, or NaN) instead of
+// silently propagating them through the rest of the evaluation.
+fn checked(result: f64) -> Result<f64, ExprError> {
+    if result.is_finite() {
+        Ok(result)
+    } else {
+        Err(ExprError::Overflow)
+    }
+}
+
 struct Tokenizer<'a> {
     tokens: Peekable<Chars<'a>>,
+    // Running char position, so tokens can carry a `Span` back to callers.
+    pos: usize,
 }
 
 impl<'a> Tokenizer<'a> {
     fn new(expr: &'a str) -> Self {
-        Self { tokens: expr.chars().peekable(), }
+        Self { tokens: expr.chars().peekable(), pos: 0 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.tokens.next();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
     }
 
     // Clean white spaces in expression
     fn consume_whitespaces(&mut self) {
         while let Some(&c) = self.tokens.peek() {
             if c.is_whitespace() {
-                self.tokens.next();
+                self.bump();
             } else {
                 break;
             }
         }
     }
 
-    // Scan number to Token
-    fn scan_numbers(&mut self ) -> Option<Token> {
+    // Scan number to Token. Recognizes `0x`/`0b`/`0o` radix prefixes for
+    // integer literals, falling back to a plain (possibly fractional) number.
+    fn scan_numbers(&mut self ) -> Result<(Token, Span), ExprError> {
+        let start = self.pos;
+
+        if self.tokens.peek() == Some(&'0') {
+            let mut lookahead = self.tokens.clone();
+            lookahead.next();
+            let radix = match lookahead.peek() {
+                Some('x') | Some('X') => Some(16),
+                Some('b') | Some('B') => Some(2),
+                Some('o') | Some('O') => Some(8),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                self.bump();
+                let prefix = self.bump().unwrap();
+                let mut digits = String::new();
+                while let Some(&c) = self.tokens.peek() {
+                    if c.is_digit(radix) {
+                        digits.push(c);
+                        self.bump();
+                    } else {
+                        break;
+                    }
+                }
+                return match i64::from_str_radix(&digits, radix) {
+                    Ok(n) => Ok((Token::Number(n as f64), (start, self.pos))),
+                    Err(_) => Err(ExprError::UnexpectedCharacter { character: prefix, position: start + 1 }),
+                };
+            }
+        }
+
         let mut num = String::new();
         while let Some(&c) = self.tokens.peek() {
-            if c.is_numeric(){
+            if c.is_numeric() || c == '.' {
                 num.push(c);
-                self.tokens.next();
+                self.bump();
             } else {
                 break;
             }
         }
 
         match num.parse() {
-            Ok(n) => Some(Token::Number(n)),
-            Err(_) => None
+            Ok(n) => Ok((Token::Number(n), (start, self.pos))),
+            Err(_) => Err(ExprError::UnexpectedCharacter {
+                character: num.chars().next().unwrap_or_default(),
+                position: start,
+            }),
         }
     }
 
-    // Scan operators to Token
-    fn scan_operator(&mut self ) -> Option<Token> {
-        match self.tokens.next() {
-            Some('+') => Some(Token::Plus),
-            Some('-') => Some(Token::Minus),
-            Some('*') => Some(Token::Mutiply),
-            Some('/') => Some(Token::Divide),
-            Some('^') => Some(Token::Power),
-            Some('(') => Some(Token::LeftParen),
-            Some(')') => Some(Token::RightParen),
-            _ => None,
+    // Scan an identifier (variable or function name): an alphabetic/underscore
+    // run followed by alphanumeric/underscore characters.
+    fn scan_identifier(&mut self) -> Result<(Token, Span), ExprError> {
+        let start = self.pos;
+        let mut name = String::new();
+        while let Some(&c) = self.tokens.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+                self.bump();
+            } else {
+                break;
+            }
         }
+        Ok((Token::Identifier(name), (start, self.pos)))
+    }
+
+    // Scan operators to Token
+    fn scan_operator(&mut self ) -> Result<(Token, Span), ExprError> {
+        let start = self.pos;
+        let c = self.bump().expect("caller already confirmed a char is present");
+
+        let token = match c {
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Mutiply,
+            '/' => Token::Divide,
+            '^' => Token::Power,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '|' => Token::Pipe,
+            '&' => Token::Ampersand,
+            '~' => Token::Xor,
+            ',' => Token::Comma,
+            '<' => {
+                if self.tokens.peek() == Some(&'=') {
+                    self.bump();
+                    Token::Le
+                } else {
+                    Token::Lt
+                }
+            }
+            '>' => {
+                if self.tokens.peek() == Some(&'=') {
+                    self.bump();
+                    Token::Ge
+                } else {
+                    Token::Gt
+                }
+            }
+            '=' if self.tokens.peek() == Some(&'=') => {
+                self.bump();
+                Token::Eq
+            }
+            '!' if self.tokens.peek() == Some(&'=') => {
+                self.bump();
+                Token::Ne
+            }
+            _ => return Err(ExprError::UnexpectedCharacter { character: c, position: start }),
+        };
+        Ok((token, (start, self.pos)))
     }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token;
+    type Item = Result<(Token, Span), ExprError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.consume_whitespaces();
 
         // parse Token to correct type
         match self.tokens.peek() {
-            Some(c) if c.is_numeric() => self.scan_numbers(),
-            Some(_) => self.scan_operator(),
+            Some(c) if c.is_numeric() => Some(self.scan_numbers()),
+            Some(c) if c.is_alphabetic() || *c == '_' => Some(self.scan_identifier()),
+            Some(_) => Some(self.scan_operator()),
             None => return None,
         }
     }
 }
 
+// AST node produced by the parser. Keeping this decoupled from evaluation
+// lets a parsed expression be inspected, transformed, or re-evaluated many
+// times without re-tokenizing the source.
+#[derive(Debug, Clone)]
+enum Node {
+    Add(Box<Node>, Box<Node>),
+    Subtract(Box<Node>, Box<Node>),
+    Multiply(Box<Node>, Box<Node>),
+    Divide(Box<Node>, Box<Node>),
+    Power(Box<Node>, Box<Node>),
+    Negative(Box<Node>),
+    Abs(Box<Node>),
+    BitAnd(Box<Node>, Box<Node>),
+    BitOr(Box<Node>, Box<Node>),
+    BitXor(Box<Node>, Box<Node>),
+    LessThan(Box<Node>, Box<Node>),
+    LessEqual(Box<Node>, Box<Node>),
+    GreaterThan(Box<Node>, Box<Node>),
+    GreaterEqual(Box<Node>, Box<Node>),
+    Equal(Box<Node>, Box<Node>),
+    NotEqual(Box<Node>, Box<Node>),
+    Number(f64),
+    Variable(String),
+    Call(String, Vec<Node>),
+}
+
+type BuiltinFn = fn(&[f64]) -> Result<f64, ExprError>;
+
+// Maps variable names to numbers and function names to built-in closures, so
+// expressions like `x * 2 + max(a, b)` can be evaluated against caller state.
+#[derive(Clone)]
+pub struct Environment {
+    variables: HashMap<String, f64>,
+    functions: HashMap<String, BuiltinFn>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        let mut functions: HashMap<String, BuiltinFn> = HashMap::new();
+        functions.insert("sqrt".to_string(), builtin_sqrt as BuiltinFn);
+        functions.insert("abs".to_string(), builtin_abs as BuiltinFn);
+        functions.insert("min".to_string(), builtin_min as BuiltinFn);
+        functions.insert("max".to_string(), builtin_max as BuiltinFn);
+        functions.insert("pow".to_string(), builtin_pow as BuiltinFn);
+        Self { variables: HashMap::new(), functions }
+    }
+
+    pub fn set_variable(&mut self, name: impl Into<String>, value: f64) {
+        self.variables.insert(name.into(), value);
+    }
+
+    fn variable(&self, name: &str) -> Result<f64, ExprError> {
+        self.variables
+            .get(name)
+            .copied()
+            .ok_or_else(|| ExprError::Parse(format!("Unknown variable '{}'", name), None))
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Result<f64, ExprError> {
+        let f = self
+            .functions
+            .get(name)
+            .ok_or_else(|| ExprError::Parse(format!("Unknown function '{}'", name), None))?;
+        f(args)
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn arity_error(name: &str, expected: usize, got: usize) -> ExprError {
+    ExprError::Parse(
+        format!("'{}' expects {} argument(s), got {}", name, expected, got),
+        None,
+    )
+}
+
+fn builtin_sqrt(args: &[f64]) -> Result<f64, ExprError> {
+    match args {
+        [x] => checked(x.sqrt()),
+        _ => Err(arity_error("sqrt", 1, args.len())),
+    }
+}
+
+fn builtin_abs(args: &[f64]) -> Result<f64, ExprError> {
+    match args {
+        [x] => checked(x.abs()),
+        _ => Err(arity_error("abs", 1, args.len())),
+    }
+}
+
+fn builtin_min(args: &[f64]) -> Result<f64, ExprError> {
+    match args {
+        [a, b] => checked(a.min(*b)),
+        _ => Err(arity_error("min", 2, args.len())),
+    }
+}
+
+fn builtin_max(args: &[f64]) -> Result<f64, ExprError> {
+    match args {
+        [a, b] => checked(a.max(*b)),
+        _ => Err(arity_error("max", 2, args.len())),
+    }
+}
+
+fn builtin_pow(args: &[f64]) -> Result<f64, ExprError> {
+    match args {
+        [base, exp] => checked(base.powf(*exp)),
+        _ => Err(arity_error("pow", 2, args.len())),
+    }
+}
+
+// Walk the AST and fold it down to a single value.
+fn eval(node: &Node, env: &Environment) -> Result<f64, ExprError> {
+    match node {
+        Node::Number(n) => Ok(*n),
+        Node::Negative(n) => Ok(-eval(n, env)?),
+        Node::Abs(n) => Ok(eval(n, env)?.abs()),
+        Node::Add(l, r) => eval_binary(Token::Plus, l, r, env),
+        Node::Subtract(l, r) => eval_binary(Token::Minus, l, r, env),
+        Node::Multiply(l, r) => eval_binary(Token::Mutiply, l, r, env),
+        Node::Divide(l, r) => eval_binary(Token::Divide, l, r, env),
+        Node::Power(l, r) => eval_binary(Token::Power, l, r, env),
+        Node::BitAnd(l, r) => eval_binary(Token::Ampersand, l, r, env),
+        Node::BitOr(l, r) => eval_binary(Token::Pipe, l, r, env),
+        Node::BitXor(l, r) => eval_binary(Token::Xor, l, r, env),
+        Node::LessThan(l, r) => eval_binary(Token::Lt, l, r, env),
+        Node::LessEqual(l, r) => eval_binary(Token::Le, l, r, env),
+        Node::GreaterThan(l, r) => eval_binary(Token::Gt, l, r, env),
+        Node::GreaterEqual(l, r) => eval_binary(Token::Ge, l, r, env),
+        Node::Equal(l, r) => eval_binary(Token::Eq, l, r, env),
+        Node::NotEqual(l, r) => eval_binary(Token::Ne, l, r, env),
+        Node::Variable(name) => env.variable(name),
+        Node::Call(name, args) => {
+            let values = args
+                .iter()
+                .map(|arg| eval(arg, env))
+                .collect::<Result<Vec<f64>, ExprError>>()?;
+            env.call(name, &values)
+        }
+    }
+}
+
+fn eval_binary(op: Token, l: &Node, r: &Node, env: &Environment) -> Result<f64, ExprError> {
+    let lv = eval(l, env)?;
+    let rv = eval(r, env)?;
+    op.compute(lv, rv)
+}
+
 struct Expr<'a> {
     src: &'a str,
     iter: Peekable<Tokenizer<'a>>,
+    env: Environment,
+    // How many `|...|` absolute-value bars we're currently nested inside.
+    // While positive, a bare `Pipe` terminates the current bar instead of
+    // being treated as the bitwise-OR operator.
+    abs_depth: usize,
 }
 
 impl<'a> Expr<'a> {
-    pub fn new(src: &'a str) -> Self {
-        Self { 
+    pub fn new(src: &'a str, env: Option<Environment>) -> Self {
+        Self {
             src,
-            iter: Tokenizer::new(src).peekable() 
+            iter: Tokenizer::new(src).peekable(),
+            env: env.unwrap_or_default(),
+            abs_depth: 0,
         }
     }
 
@@ -176,28 +520,60 @@ impl<'a> Expr<'a> {
         self.iter = Tokenizer::new(self.src).peekable();
     }
 
-    // Compute expressions, get results
-    pub fn evaluation(&mut self) -> Result<i32, ExprError> {
-        let result = self.compute_expression(1)?;
+    // Peek the next token without consuming it, surfacing tokenizer errors
+    // (e.g. an unrecognized character) as soon as they're reached.
+    fn peek_token(&mut self) -> Result<Option<(Token, Span)>, ExprError> {
+        match self.iter.peek() {
+            None => Ok(None),
+            Some(Ok(tok)) => Ok(Some(tok.clone())),
+            Some(Err(_)) => Err(self.iter.next().unwrap().unwrap_err()),
+        }
+    }
+
+    // Consume the token already confirmed present by `peek_token`.
+    fn advance(&mut self) -> (Token, Span) {
+        self.iter.next().expect("advance called without a peeked token").expect("advance called on an error token")
+    }
 
-        if self.iter.peek().is_some() {
-            return Err(ExprError::Parse("Unexpected end of expr".into()));
+    // Parse the expression into an AST, without evaluating it.
+    pub fn parse(&mut self) -> Result<Node, ExprError> {
+        let node = self.compute_expression(1)?;
+
+        if let Some((_, span)) = self.peek_token()? {
+            return Err(ExprError::Parse(
+                format!("Unexpected trailing input at {}..{}", span.0, span.1),
+                Some(span),
+            ));
         }
-        Ok(result)
+        Ok(node)
     }
 
-    pub fn compute_expression(&mut self, min_prec: i32) -> Result<i32, ExprError> {
+    // Convenience: parse then eval in one call, for backward compatibility.
+    pub fn evaluation(&mut self) -> Result<f64, ExprError> {
+        let node = self.parse()?;
+        eval(&node, &self.env)
+    }
+
+    // Evaluate and interpret the result as a boolean: nonzero is true.
+    pub fn evaluate_bool(&mut self) -> Result<bool, ExprError> {
+        self.evaluation().map(is_truthy)
+    }
+
+    pub fn compute_expression(&mut self, min_prec: i32) -> Result<Node, ExprError> {
         // Atom in the left
         let mut atom_l = self.compute_atom()?;
 
         loop {
-            let cur_token = self.iter.peek();
-            if cur_token.is_none() {
-                break;
-            }
-            let token = *cur_token.unwrap();
+            let (token, span) = match self.peek_token()? {
+                None => break,
+                Some(t) => t,
+            };
+
+            // Inside `|...|`, a bare `|` closes the bars rather than being
+            // parsed as the bitwise-OR operator.
+            let closes_abs_bars = matches!(token, Token::Pipe) && self.abs_depth > 0;
 
-            if !token.is_operator() || token.precedence() < min_prec {
+            if !token.is_operator() || token.precedence() < min_prec || closes_abs_bars {
                 break;
             }
 
@@ -206,40 +582,122 @@ impl<'a> Expr<'a> {
                 next_prec += 1;
             }
 
-            self.iter.next();
+            self.advance();
 
             // Atom in the right
             let atom_r = self.compute_expression(next_prec)?;
 
-            // Compute the value in left and right
-            match token.compute(atom_l, atom_r) {
-                Some(re) => atom_l = re,
-                None => return Err(ExprError::Parse("Unknown expression".into())),
-            }
+            // Build the node for the left and right operands
+            atom_l = match token {
+                Token::Plus => Node::Add(Box::new(atom_l), Box::new(atom_r)),
+                Token::Minus => Node::Subtract(Box::new(atom_l), Box::new(atom_r)),
+                Token::Mutiply => Node::Multiply(Box::new(atom_l), Box::new(atom_r)),
+                Token::Divide => Node::Divide(Box::new(atom_l), Box::new(atom_r)),
+                Token::Power => Node::Power(Box::new(atom_l), Box::new(atom_r)),
+                Token::Ampersand => Node::BitAnd(Box::new(atom_l), Box::new(atom_r)),
+                Token::Pipe => Node::BitOr(Box::new(atom_l), Box::new(atom_r)),
+                Token::Xor => Node::BitXor(Box::new(atom_l), Box::new(atom_r)),
+                Token::Lt => Node::LessThan(Box::new(atom_l), Box::new(atom_r)),
+                Token::Le => Node::LessEqual(Box::new(atom_l), Box::new(atom_r)),
+                Token::Gt => Node::GreaterThan(Box::new(atom_l), Box::new(atom_r)),
+                Token::Ge => Node::GreaterEqual(Box::new(atom_l), Box::new(atom_r)),
+                Token::Eq => Node::Equal(Box::new(atom_l), Box::new(atom_r)),
+                Token::Ne => Node::NotEqual(Box::new(atom_l), Box::new(atom_r)),
+                _ => return Err(ExprError::Parse("Unknown expression".into(), Some(span))),
+            };
         }
-        Ok(atom_l) 
+        Ok(atom_l)
 
     }
 
-    pub fn compute_atom(&mut self) -> Result<i32, ExprError> {
-        match self.iter.peek() {
+    pub fn compute_atom(&mut self) -> Result<Node, ExprError> {
+        let (token, span) = match self.peek_token()? {
+            Some(t) => t,
+            None => return Err(ExprError::Parse("Expecting a number, identifier, '-', '|' or left paren, got end of expr".into(), None)),
+        };
+
+        match token {
             // Number
-            Some(Token::Number(n)) => {
-                let val = *n;
-                self.iter.next();
-                return Ok(val);
+            Token::Number(n) => {
+                self.advance();
+                Ok(Node::Number(n))
+            }
+            // Unary minus: binds tighter than the binary operators but looser
+            // than `^`, so `-2^2` parses as `-(2^2)`.
+            Token::Minus => {
+                self.advance();
+                let operand = self.compute_expression(Token::Power.precedence())?;
+                Ok(Node::Negative(Box::new(operand)))
             }
             // Left Paren
-            Some(Token::LeftParen) => {
-                self.iter.next();
+            Token::LeftParen => {
+                self.advance();
                 let result = self.compute_expression(1)?;
-                match self.iter.next() {
-                    Some(Token::RightParen) => (),
-                    _ => return Err(ExprError::Parse("Unexpected character".into()))
+                match self.peek_token()? {
+                    Some((Token::RightParen, _)) => { self.advance(); }
+                    Some((_, span)) => {
+                        return Err(ExprError::Parse(
+                            format!("Expecting ')' at {}..{}", span.0, span.1),
+                            Some(span),
+                        ))
+                    }
+                    None => return Err(ExprError::Parse("Expecting ')', got end of expr".into(), None)),
                 }
                 Ok(result)
             }
-            _ => return Err(ExprError::Parse("Expecting a number or left paren".into()))
+            // Absolute value, delimited by bars: `|expr|`
+            Token::Pipe => {
+                self.advance();
+                self.abs_depth += 1;
+                let inner = self.compute_expression(1);
+                self.abs_depth -= 1;
+                let inner = inner?;
+                match self.peek_token()? {
+                    Some((Token::Pipe, _)) => { self.advance(); }
+                    Some((_, span)) => {
+                        return Err(ExprError::Parse(
+                            format!("Expecting closing '|' at {}..{}", span.0, span.1),
+                            Some(span),
+                        ))
+                    }
+                    None => return Err(ExprError::Parse("Expecting closing '|', got end of expr".into(), None)),
+                }
+                Ok(Node::Abs(Box::new(inner)))
+            }
+            // Identifier: a function call if followed by `(`, else a variable.
+            Token::Identifier(name) => {
+                self.advance();
+                if let Some((Token::LeftParen, _)) = self.peek_token()? {
+                    self.advance();
+                    let mut args = Vec::new();
+                    if !matches!(self.peek_token()?, Some((Token::RightParen, _))) {
+                        loop {
+                            args.push(self.compute_expression(1)?);
+                            match self.peek_token()? {
+                                Some((Token::Comma, _)) => { self.advance(); }
+                                _ => break,
+                            }
+                        }
+                    }
+                    match self.peek_token()? {
+                        Some((Token::RightParen, _)) => { self.advance(); }
+                        Some((_, span)) => {
+                            return Err(ExprError::Parse(
+                                format!("Expecting ')' at {}..{}", span.0, span.1),
+                                Some(span),
+                            ))
+                        }
+                        None => return Err(ExprError::Parse("Expecting ')', got end of expr".into(), None)),
+                    }
+                    Ok(Node::Call(name, args))
+                } else {
+                    Ok(Node::Variable(name))
+                }
+            }
+            _ => Err(ExprError::Parse(
+                format!("Expecting a number, identifier, '-', '|' or left paren at {}..{}", span.0, span.1),
+                Some(span),
+            )),
         }
     }
 }
@@ -247,9 +705,15 @@ impl<'a> Expr<'a> {
 fn main() {
     println!("Hello, world!");
     let src = "83 - 5 + 3 * 10 + (83 - 73) / 5 + 35"; 
-    let mut expr = Expr::new(src);
+    let mut expr = Expr::new(src, None);
     for item in expr.iter.by_ref() {
-        print!("{}", item);
+        match item {
+            Ok((token, _span)) => print!("{}", token),
+            Err(e) => {
+                print!("<{}>", e);
+                break;
+            }
+        }
     }
     
     // New line
@@ -257,4 +721,101 @@ fn main() {
     expr.reset();
     let result = expr.evaluation();
     println!("Result = {:?}", result.unwrap());
+
+    let truthy = Expr::new("1 < 2", None).evaluate_bool();
+    println!("Truthy = {:?}", truthy.unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_src(src: &str) -> Result<f64, ExprError> {
+        Expr::new(src, None).evaluation()
+    }
+
+    #[test]
+    fn abs_bars_evaluate_their_contents() {
+        assert_eq!(eval_src("|3|").unwrap(), 3.0);
+        assert_eq!(eval_src("|-3|").unwrap(), 3.0);
+    }
+
+    #[test]
+    fn abs_bars_do_not_swallow_a_following_bitwise_or() {
+        // Regression test: the closing `|` of `|3|` must terminate the bars,
+        // not be parsed as a binary bitwise-OR against whatever follows.
+        assert_eq!(eval_src("1 | |3|").unwrap(), 3.0);
+        assert_eq!(eval_src("|2 + 3| * 2").unwrap(), 10.0);
+    }
+
+    #[test]
+    fn bitwise_operators_sit_below_arithmetic() {
+        assert_eq!(eval_src("1 | 2 + 3").unwrap(), 5.0);
+        assert_eq!(eval_src("0b101 & 0b110").unwrap(), 4.0);
+        assert_eq!(eval_src("5 ~ 3").unwrap(), 6.0);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert_eq!(eval_src("1 / 0").unwrap_err(), ExprError::DivisionByZero);
+    }
+
+    #[test]
+    fn division_overflow_is_an_error() {
+        assert_eq!(eval_src("10^300 / 0.0000000001").unwrap_err(), ExprError::Overflow);
+    }
+
+    #[test]
+    fn builtin_function_overflow_is_an_error() {
+        // sqrt of a negative number is NaN, which `checked` must reject rather
+        // than passing through silently.
+        assert_eq!(eval_src("sqrt(-1)").unwrap_err(), ExprError::Overflow);
+    }
+
+    #[test]
+    fn unary_minus_binds_tighter_than_binary_ops_but_looser_than_power() {
+        assert_eq!(eval_src("-2^2").unwrap(), -4.0);
+        assert_eq!(eval_src("-2^2 + 1").unwrap(), -3.0);
+    }
+
+    #[test]
+    fn variables_and_function_calls_are_resolved_via_the_environment() {
+        let mut env = Environment::new();
+        env.set_variable("x", 3.0);
+        env.set_variable("a", 2.0);
+        env.set_variable("b", 7.0);
+        let result = Expr::new("x * 2 + max(a, b)", Some(env)).evaluation();
+        assert_eq!(result.unwrap(), 13.0);
+    }
+
+    #[test]
+    fn unknown_variable_is_an_error() {
+        assert!(eval_src("unknown_var").is_err());
+    }
+
+    #[test]
+    fn unknown_function_is_an_error() {
+        assert!(eval_src("nope(1)").is_err());
+    }
+
+    #[test]
+    fn wrong_arity_is_an_error() {
+        assert!(eval_src("sqrt(1, 2)").is_err());
+    }
+
+    #[test]
+    fn comparison_operators_yield_zero_or_one() {
+        assert_eq!(eval_src("1 < 2").unwrap(), 1.0);
+        assert_eq!(eval_src("2 <= 2").unwrap(), 1.0);
+        assert_eq!(eval_src("3 > 2").unwrap(), 1.0);
+        assert_eq!(eval_src("3 >= 4").unwrap(), 0.0);
+        assert_eq!(eval_src("2 == 2").unwrap(), 1.0);
+        assert_eq!(eval_src("2 != 3").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn evaluate_bool_treats_nonzero_as_truthy() {
+        assert!(!Expr::new("0", None).evaluate_bool().unwrap());
+        assert!(Expr::new("1 < 2", None).evaluate_bool().unwrap());
+    }
 }